@@ -1,17 +1,169 @@
-use std::os::raw::{c_char, c_int, c_ulong, c_void};
+use std::os::raw::{c_char, c_int, c_long, c_uchar, c_uint, c_ulong, c_void};
 
 pub type XID = c_ulong;
 pub type Window = XID;
+pub type Atom = c_ulong;
+pub type Time = c_ulong;
 
 #[repr(C)]
 pub struct Display {
     private: [u8; 0],
 }
 
+#[repr(C)]
+pub struct Screen {
+    private: [u8; 0],
+}
+
+/// The standard Xlib event union. Only the leading `type_` tag is given a
+/// field of its own; everything else lives in `pad`, which is sized to fit
+/// the largest concrete event struct (e.g. `XKeyEvent`, `XClientMessageEvent`).
+/// Safe code reads `type_` to find out which event occurred, then reinterprets
+/// the same memory as the matching concrete struct below.
+#[repr(C)]
+pub union XEvent {
+    pub type_: c_int,
+    pad: [c_long; 24],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XKeyEvent {
+    pub type_: c_int,
+    pub serial: c_ulong,
+    pub send_event: c_int,
+    pub display: *mut Display,
+    pub window: Window,
+    pub root: Window,
+    pub subwindow: Window,
+    pub time: Time,
+    pub x: c_int,
+    pub y: c_int,
+    pub x_root: c_int,
+    pub y_root: c_int,
+    pub state: c_uint,
+    pub keycode: c_uint,
+    pub same_screen: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XButtonEvent {
+    pub type_: c_int,
+    pub serial: c_ulong,
+    pub send_event: c_int,
+    pub display: *mut Display,
+    pub window: Window,
+    pub root: Window,
+    pub subwindow: Window,
+    pub time: Time,
+    pub x: c_int,
+    pub y: c_int,
+    pub x_root: c_int,
+    pub y_root: c_int,
+    pub state: c_uint,
+    pub button: c_uint,
+    pub same_screen: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XExposeEvent {
+    pub type_: c_int,
+    pub serial: c_ulong,
+    pub send_event: c_int,
+    pub display: *mut Display,
+    pub window: Window,
+    pub x: c_int,
+    pub y: c_int,
+    pub width: c_int,
+    pub height: c_int,
+    pub count: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XConfigureEvent {
+    pub type_: c_int,
+    pub serial: c_ulong,
+    pub send_event: c_int,
+    pub display: *mut Display,
+    pub event: Window,
+    pub window: Window,
+    pub x: c_int,
+    pub y: c_int,
+    pub width: c_int,
+    pub height: c_int,
+    pub border_width: c_int,
+    pub above: Window,
+    pub override_redirect: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XClientMessageEvent {
+    pub type_: c_int,
+    pub serial: c_ulong,
+    pub send_event: c_int,
+    pub display: *mut Display,
+    pub window: Window,
+    pub message_type: Atom,
+    pub format: c_int,
+    pub data: [c_long; 5],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XErrorEvent {
+    pub type_: c_int,
+    pub display: *mut Display,
+    pub resourceid: XID,
+    pub serial: c_ulong,
+    pub error_code: c_uchar,
+    pub request_code: c_uchar,
+    pub minor_code: c_uchar,
+}
+
+/// Signature Xlib requires for the process-wide error handler installed
+/// through `XSetErrorHandler`.
+pub type XErrorHandler = extern "system" fn(*mut Display, *mut XErrorEvent) -> c_int;
+
 #[link(name = "X11")]
 extern "system" {
     pub fn XOpenDisplay(name: *const c_char) -> *mut Display;
     pub fn XCloseDisplay(display: *mut Display) -> c_int;
     pub fn XFree(data: *mut c_void) -> c_int;
     pub fn XDefaultScreen(display: *mut Display) -> c_int;
+    pub fn XNextEvent(display: *mut Display, event_return: *mut XEvent) -> c_int;
+    pub fn XPending(display: *mut Display) -> c_int;
+    pub fn XEventsQueued(display: *mut Display, mode: c_int) -> c_int;
+    pub fn XSetErrorHandler(handler: XErrorHandler) -> XErrorHandler;
+    pub fn XConnectionNumber(display: *mut Display) -> c_int;
+    pub fn XInternAtom(
+        display: *mut Display,
+        atom_name: *const c_char,
+        only_if_exists: c_int,
+    ) -> Atom;
+    pub fn XInternAtoms(
+        display: *mut Display,
+        names: *mut *mut c_char,
+        count: c_int,
+        only_if_exists: c_int,
+        atoms_return: *mut Atom,
+    ) -> c_int;
+    pub fn XGetAtomName(display: *mut Display, atom: Atom) -> *mut c_char;
+    pub fn XRootWindow(display: *mut Display, screen_number: c_int) -> Window;
+    pub fn XDefaultRootWindow(display: *mut Display) -> Window;
+    pub fn XScreenOfDisplay(display: *mut Display, screen_number: c_int) -> *mut Screen;
+    pub fn XDisplayWidth(display: *mut Display, screen_number: c_int) -> c_int;
+    pub fn XDisplayHeight(display: *mut Display, screen_number: c_int) -> c_int;
+    pub fn XScreenCount(display: *mut Display) -> c_int;
+    pub fn XQueryTree(
+        display: *mut Display,
+        w: Window,
+        root_return: *mut Window,
+        parent_return: *mut Window,
+        children_return: *mut *mut Window,
+        nchildren_return: *mut c_uint,
+    ) -> c_int;
 }
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::io::{AsRawFd, RawFd};
 
 pub mod cdef;
 
@@ -29,6 +32,7 @@ pub struct XlibError {
     message: String,
     kind: ErrorKind,
     side: Option<Box<dyn std::error::Error>>,
+    protocol: Option<ProtocolError>,
 }
 
 impl std::error::Error for XlibError {}
@@ -39,9 +43,94 @@ impl fmt::Display for XlibError {
     }
 }
 
+impl XlibError {
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Details specific to asynchronously reported X protocol errors, i.e.
+    /// errors produced through `x_set_error_handler`. `None` for errors
+    /// raised locally by this crate, such as `InvalidArgumentValue`.
+    pub fn protocol(&self) -> Option<&ProtocolError> {
+        self.protocol.as_ref()
+    }
+
+    fn from_x_error(event: cdef::XErrorEvent) -> XlibError {
+        let kind = ErrorKind::from_x_error_code(event.error_code);
+        XlibError {
+            message: format!("X protocol error: {:?}", kind),
+            kind,
+            side: None,
+            protocol: Some(ProtocolError {
+                request_code: event.request_code,
+                minor_code: event.minor_code,
+                resource_id: event.resourceid,
+                serial: event.serial,
+            }),
+        }
+    }
+}
+
+/// The kind of an `XlibError`. `InvalidArgumentValue` is raised locally by
+/// this crate; every other variant is the error code reported by the X
+/// server, translated from `XErrorEvent::error_code`.
 #[derive(Debug)]
 pub enum ErrorKind {
     InvalidArgumentValue,
+    BadRequest,
+    BadValue,
+    BadWindow,
+    BadPixmap,
+    BadAtom,
+    BadCursor,
+    BadFont,
+    BadMatch,
+    BadDrawable,
+    BadAccess,
+    BadAlloc,
+    BadColor,
+    BadGC,
+    BadIdChoice,
+    BadName,
+    BadLength,
+    BadImplementation,
+    /// An error code this crate doesn't recognize, carried through as-is.
+    UnknownProtocolError(u8),
+}
+
+impl ErrorKind {
+    fn from_x_error_code(code: u8) -> ErrorKind {
+        match code {
+            1 => ErrorKind::BadRequest,
+            2 => ErrorKind::BadValue,
+            3 => ErrorKind::BadWindow,
+            4 => ErrorKind::BadPixmap,
+            5 => ErrorKind::BadAtom,
+            6 => ErrorKind::BadCursor,
+            7 => ErrorKind::BadFont,
+            8 => ErrorKind::BadMatch,
+            9 => ErrorKind::BadDrawable,
+            10 => ErrorKind::BadAccess,
+            11 => ErrorKind::BadAlloc,
+            12 => ErrorKind::BadColor,
+            13 => ErrorKind::BadGC,
+            14 => ErrorKind::BadIdChoice,
+            15 => ErrorKind::BadName,
+            16 => ErrorKind::BadLength,
+            17 => ErrorKind::BadImplementation,
+            other => ErrorKind::UnknownProtocolError(other),
+        }
+    }
+}
+
+/// The fields an `XErrorEvent` carries beyond its error code: which request
+/// triggered it and which resource, if any, it was about.
+#[derive(Debug, Clone, Copy)]
+pub struct ProtocolError {
+    pub request_code: u8,
+    pub minor_code: u8,
+    pub resource_id: cdef::XID,
+    pub serial: std::os::raw::c_ulong,
 }
 
 /// An xlib equivalent to Rust's Box that uses XFree to free memory.
@@ -52,7 +141,6 @@ pub struct XBox<T: ?Sized> {
     phantom: std::marker::PhantomData<*const T>,
 }
 
-#[allow(dead_code)]
 struct XBoxFatPtr {
     data: *const std::ffi::c_void,
     length: usize,
@@ -100,20 +188,14 @@ impl<T> std::ops::Deref for XBox<[T]> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
-        unsafe {
-            // FIXME: incredibly unsafe and ugly
-            let temp: &*const Self::Target = std::mem::transmute(&self.data);
-            &*(*temp)
-        }
+        unsafe { &*std::ptr::slice_from_raw_parts(self.data.data as *const T, self.data.length) }
     }
 }
 
 impl<T> std::ops::DerefMut for XBox<[T]> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe {
-            // FIXME: incredibly unsafe and ugly
-            let temp: &*mut Self::Target = std::mem::transmute(&self.data);
-            &mut *(*temp)
+            &mut *std::ptr::slice_from_raw_parts_mut(self.data.data as *mut T, self.data.length)
         }
     }
 }
@@ -126,6 +208,62 @@ impl<T: ?Sized> Drop for XBox<T> {
     }
 }
 
+/// An owned connection to the X server, as returned by `x_open_display`.
+///
+/// Unlike `DoNotFree`, which marks resources Xlib itself owns, a `Display`
+/// owns the connection outright: dropping it calls `XCloseDisplay`, so a
+/// forgotten `x_close_display` call can no longer leak the connection.
+/// `Display` derefs to `DoNotFree<cdef::Display>`, so it works with every
+/// function in this crate that takes one.
+pub struct Display {
+    inner: DoNotFree<cdef::Display>,
+}
+
+impl Display {
+    /// Returns the raw display pointer without giving up ownership.
+    pub fn as_raw(&self) -> *mut cdef::Display {
+        self.inner.data
+    }
+
+    /// Gives up ownership of the connection, returning the raw display
+    /// pointer without closing it. Use this to hand the connection off to
+    /// other X libraries that expect to manage it themselves; the caller
+    /// becomes responsible for eventually calling `XCloseDisplay`.
+    pub fn into_raw(self) -> *mut cdef::Display {
+        let raw = self.inner.data;
+        std::mem::forget(self);
+        raw
+    }
+}
+
+impl std::ops::Deref for Display {
+    type Target = DoNotFree<cdef::Display>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl std::ops::DerefMut for Display {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl Drop for Display {
+    fn drop(&mut self) {
+        unsafe {
+            cdef::XCloseDisplay(self.inner.data);
+        }
+    }
+}
+
+impl AsRawFd for Display {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
 /// Retrieves a connection, also known as a display, to the X server.
 ///
 /// # Parameters
@@ -161,11 +299,11 @@ impl<T: ?Sized> Drop for XBox<T> {
 /// argument is returned by the x_default_screen function. You can access elements
 /// of the Display and Screen structures only by using the information functions.
 ///
-/// Use x_close_display before exiting the program to destroy all resoures created
-/// on the display.
-pub fn x_open_display(
-    display_name: Option<&str>,
-) -> Result<Option<DoNotFree<cdef::Display>>, XlibError> {
+/// The returned Display closes the connection itself when dropped, so
+/// calling x_close_display before exiting the program is optional; use it
+/// only when you need the connection closed at a specific point rather than
+/// whenever the Display happens to go out of scope.
+pub fn x_open_display(display_name: Option<&str>) -> Result<Option<Display>, XlibError> {
     let display = match display_name {
         None => unsafe { cdef::XOpenDisplay(std::ptr::null()) },
         Some(display_name) => {
@@ -175,6 +313,7 @@ pub fn x_open_display(
                         message: String::from("Failed to convert display_name to CString."),
                         kind: ErrorKind::InvalidArgumentValue,
                         side: Some(Box::new(err)),
+                        protocol: None,
                     });
                 }
                 Ok(display_name) => display_name,
@@ -187,14 +326,399 @@ pub fn x_open_display(
     if display.is_null() {
         Ok(None)
     } else {
-        Ok(Some(DoNotFree { data: display }))
+        Ok(Some(Display {
+            inner: DoNotFree { data: display },
+        }))
     }
 }
 
-pub fn x_close_display(display: DoNotFree<cdef::Display>) -> i32 {
-    unsafe { cdef::XCloseDisplay(display.data) }
+/// Explicitly closes `display`'s connection, returning the same status code
+/// `XCloseDisplay` does. Equivalent to letting `display` drop, but useful
+/// when the connection needs to be closed at a specific point rather than
+/// whenever it happens to go out of scope.
+pub fn x_close_display(display: Display) -> i32 {
+    unsafe { cdef::XCloseDisplay(display.into_raw()) }
 }
 
 pub fn x_default_screen(display: &mut DoNotFree<cdef::Display>) -> i32 {
     unsafe { cdef::XDefaultScreen(display.data) }
 }
+
+/// Returns the id of the root window of `screen`.
+pub fn x_root_window(display: &mut DoNotFree<cdef::Display>, screen: i32) -> cdef::Window {
+    unsafe { cdef::XRootWindow(display.data, screen) }
+}
+
+/// Returns the id of the root window of the default screen, i.e. the screen
+/// `x_default_screen` reports.
+pub fn x_default_root_window(display: &mut DoNotFree<cdef::Display>) -> cdef::Window {
+    unsafe { cdef::XDefaultRootWindow(display.data) }
+}
+
+/// Returns the `Screen` structure for `screen`, server-owned for the
+/// lifetime of `display`.
+pub fn x_screen_of_display(
+    display: &mut DoNotFree<cdef::Display>,
+    screen: i32,
+) -> DoNotFree<cdef::Screen> {
+    let data = unsafe { cdef::XScreenOfDisplay(display.data, screen) };
+    DoNotFree { data }
+}
+
+/// Returns the width and height, in pixels, of `screen`.
+pub fn x_display_dimensions(display: &mut DoNotFree<cdef::Display>, screen: i32) -> (i32, i32) {
+    let width = unsafe { cdef::XDisplayWidth(display.data, screen) };
+    let height = unsafe { cdef::XDisplayHeight(display.data, screen) };
+    (width, height)
+}
+
+/// Returns the number of screens on `display`.
+pub fn x_screen_count(display: &mut DoNotFree<cdef::Display>) -> i32 {
+    unsafe { cdef::XScreenCount(display.data) }
+}
+
+/// Queries the root, parent, and children of `window`, giving back the
+/// children as an `XBox<[Window]>` that's freed with `XFree` on drop.
+pub fn x_query_tree(
+    display: &mut DoNotFree<cdef::Display>,
+    window: cdef::Window,
+) -> (cdef::Window, cdef::Window, XBox<[cdef::Window]>) {
+    let mut root: cdef::Window = 0;
+    let mut parent: cdef::Window = 0;
+    let mut children: *mut cdef::Window = std::ptr::null_mut();
+    let mut nchildren: std::os::raw::c_uint = 0;
+    unsafe {
+        cdef::XQueryTree(
+            display.data,
+            window,
+            &mut root,
+            &mut parent,
+            &mut children,
+            &mut nchildren,
+        );
+    }
+    let children = XBox::boxed_slice_from_raw(children, nchildren as usize);
+    (root, parent, children)
+}
+
+/// Xlib's event-queue mode for `XEventsQueued`: only report events already in
+/// the local queue, never reading from the connection. This is what makes
+/// `x_check_pending` genuinely non-blocking.
+const QUEUED_ALREADY: i32 = 0;
+
+const KEY_PRESS: i32 = 2;
+const KEY_RELEASE: i32 = 3;
+const BUTTON_PRESS: i32 = 4;
+const BUTTON_RELEASE: i32 = 5;
+const EXPOSE: i32 = 12;
+const CONFIGURE_NOTIFY: i32 = 22;
+const CLIENT_MESSAGE: i32 = 33;
+
+/// A strongly-typed view of an Xlib event, produced by matching on the raw
+/// `cdef::XEvent` union's `type_` tag and reinterpreting its payload as the
+/// matching concrete event struct.
+#[derive(Debug, Clone)]
+pub enum Event {
+    KeyPress {
+        window: cdef::Window,
+        keycode: u32,
+        state: u32,
+    },
+    KeyRelease {
+        window: cdef::Window,
+        keycode: u32,
+        state: u32,
+    },
+    ButtonPress {
+        window: cdef::Window,
+        button: u32,
+        state: u32,
+    },
+    ButtonRelease {
+        window: cdef::Window,
+        button: u32,
+        state: u32,
+    },
+    Expose {
+        window: cdef::Window,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    ConfigureNotify {
+        window: cdef::Window,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    ClientMessage {
+        window: cdef::Window,
+        message_type: cdef::Atom,
+        data: [i64; 5],
+    },
+    /// An event type this crate doesn't model yet, carrying the raw `type_` tag.
+    Unknown(i32),
+}
+
+impl Event {
+    fn from_raw(event: &cdef::XEvent) -> Event {
+        let type_ = unsafe { event.type_ };
+        match type_ {
+            KEY_PRESS | KEY_RELEASE => {
+                let e = unsafe { &*(event as *const cdef::XEvent as *const cdef::XKeyEvent) };
+                let fields = (e.window, e.keycode, e.state);
+                if type_ == KEY_PRESS {
+                    Event::KeyPress {
+                        window: fields.0,
+                        keycode: fields.1,
+                        state: fields.2,
+                    }
+                } else {
+                    Event::KeyRelease {
+                        window: fields.0,
+                        keycode: fields.1,
+                        state: fields.2,
+                    }
+                }
+            }
+            BUTTON_PRESS | BUTTON_RELEASE => {
+                let e = unsafe { &*(event as *const cdef::XEvent as *const cdef::XButtonEvent) };
+                let fields = (e.window, e.button, e.state);
+                if type_ == BUTTON_PRESS {
+                    Event::ButtonPress {
+                        window: fields.0,
+                        button: fields.1,
+                        state: fields.2,
+                    }
+                } else {
+                    Event::ButtonRelease {
+                        window: fields.0,
+                        button: fields.1,
+                        state: fields.2,
+                    }
+                }
+            }
+            EXPOSE => {
+                let e = unsafe { &*(event as *const cdef::XEvent as *const cdef::XExposeEvent) };
+                Event::Expose {
+                    window: e.window,
+                    x: e.x,
+                    y: e.y,
+                    width: e.width,
+                    height: e.height,
+                }
+            }
+            CONFIGURE_NOTIFY => {
+                let e =
+                    unsafe { &*(event as *const cdef::XEvent as *const cdef::XConfigureEvent) };
+                Event::ConfigureNotify {
+                    window: e.window,
+                    x: e.x,
+                    y: e.y,
+                    width: e.width,
+                    height: e.height,
+                }
+            }
+            CLIENT_MESSAGE => {
+                let e = unsafe {
+                    &*(event as *const cdef::XEvent as *const cdef::XClientMessageEvent)
+                };
+                Event::ClientMessage {
+                    window: e.window,
+                    message_type: e.message_type,
+                    data: e.data.map(|v| v as i64),
+                }
+            }
+            other => Event::Unknown(other),
+        }
+    }
+}
+
+/// Blocks until the next event is available on `display` and returns it,
+/// flushing the output buffer first if necessary (as `XNextEvent` does).
+pub fn x_next_event(display: &mut DoNotFree<cdef::Display>) -> Event {
+    let mut raw: cdef::XEvent = unsafe { std::mem::zeroed() };
+    unsafe { cdef::XNextEvent(display.data, &mut raw) };
+    Event::from_raw(&raw)
+}
+
+/// Returns the next event without blocking if one is already queued locally,
+/// or `None` otherwise. Unlike `XPending`, this never reads from the
+/// connection, so it won't block waiting on the server.
+pub fn x_check_pending(display: &mut DoNotFree<cdef::Display>) -> Option<Event> {
+    let queued = unsafe { cdef::XEventsQueued(display.data, QUEUED_ALREADY) };
+    if queued == 0 {
+        None
+    } else {
+        Some(x_next_event(display))
+    }
+}
+
+/// Xlib only allows a single process-wide error handler, installed through
+/// `XSetErrorHandler`. This slot holds whatever closure `x_set_error_handler`
+/// last registered, so the extern "system" trampoline below has something to
+/// dispatch to.
+static ERROR_HANDLER: std::sync::Mutex<Option<Box<dyn FnMut(cdef::XErrorEvent) + Send>>> =
+    std::sync::Mutex::new(None);
+
+extern "system" fn error_trampoline(
+    _display: *mut cdef::Display,
+    event: *mut cdef::XErrorEvent,
+) -> c_int {
+    let event = unsafe { *event };
+    if let Ok(mut slot) = ERROR_HANDLER.lock() {
+        if let Some(handler) = slot.as_mut() {
+            handler(event);
+        }
+    }
+    0
+}
+
+/// Installs `handler` as the process-wide Xlib error handler. Xlib reports
+/// protocol errors (BadWindow, BadAtom, etc.) asynchronously rather than
+/// through a function's return value, so this is the only way to observe
+/// them; each `XErrorEvent` Xlib delivers is translated into an `XlibError`
+/// before `handler` is called. Calling this again replaces whatever handler
+/// was registered before, since Xlib only supports one at a time.
+pub fn x_set_error_handler(mut handler: impl FnMut(XlibError) + Send + 'static) {
+    let boxed: Box<dyn FnMut(cdef::XErrorEvent) + Send> = Box::new(move |event| {
+        handler(XlibError::from_x_error(event));
+    });
+    *ERROR_HANDLER.lock().unwrap() = Some(boxed);
+    unsafe {
+        cdef::XSetErrorHandler(error_trampoline);
+    }
+}
+
+/// Returns the file descriptor backing the connection to the X server, for
+/// registering with `poll`/`epoll`-based event loops (`mio`, `tokio`, ...).
+///
+/// # Remarks
+/// Xlib buffers events it has already read off the socket internally, so
+/// readiness on this fd is not sufficient on its own: drain whatever is
+/// already buffered with `x_drain_pending` before waiting on the fd, or you
+/// may block forever on a fd that has nothing left to read even though Xlib
+/// is still holding queued events.
+pub fn x_connection_number(display: &DoNotFree<cdef::Display>) -> RawFd {
+    unsafe { cdef::XConnectionNumber(display.data) as RawFd }
+}
+
+impl AsRawFd for DoNotFree<cdef::Display> {
+    fn as_raw_fd(&self) -> RawFd {
+        x_connection_number(self)
+    }
+}
+
+/// Drains every event Xlib has already buffered locally, without reading
+/// from the connection. Call this before waiting on `x_connection_number`'s
+/// fd so that events Xlib already read off the socket aren't missed.
+pub fn x_drain_pending(display: &mut DoNotFree<cdef::Display>) -> Vec<Event> {
+    let mut events = Vec::new();
+    while let Some(event) = x_check_pending(display) {
+        events.push(event);
+    }
+    events
+}
+
+/// Interns `name`, returning the `Atom` the server uses for it. If
+/// `only_if_exists` is true and no atom for `name` has been interned by any
+/// client yet, returns `Ok(None)` instead of creating one.
+pub fn x_intern_atom(
+    display: &mut DoNotFree<cdef::Display>,
+    name: &str,
+    only_if_exists: bool,
+) -> Result<Option<cdef::Atom>, XlibError> {
+    let name = CString::new(name).map_err(|err| XlibError {
+        message: String::from("Failed to convert name to CString."),
+        kind: ErrorKind::InvalidArgumentValue,
+        side: Some(Box::new(err)),
+        protocol: None,
+    })?;
+    let atom = unsafe { cdef::XInternAtom(display.data, name.as_ptr(), only_if_exists as i32) };
+    if atom == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(atom))
+    }
+}
+
+/// Batch form of `x_intern_atom`: interns every name in `names` in a single
+/// round-trip to the server, returning the atoms in the same order.
+pub fn x_intern_atoms(
+    display: &mut DoNotFree<cdef::Display>,
+    names: &[&str],
+    only_if_exists: bool,
+) -> Result<Vec<cdef::Atom>, XlibError> {
+    let cstrings = names
+        .iter()
+        .map(|name| CString::new(*name))
+        .collect::<Result<Vec<CString>, _>>()
+        .map_err(|err| XlibError {
+            message: String::from("Failed to convert name to CString."),
+            kind: ErrorKind::InvalidArgumentValue,
+            side: Some(Box::new(err)),
+            protocol: None,
+        })?;
+    let mut name_ptrs: Vec<*mut c_char> =
+        cstrings.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+    let mut atoms = vec![0 as cdef::Atom; names.len()];
+    unsafe {
+        cdef::XInternAtoms(
+            display.data,
+            name_ptrs.as_mut_ptr(),
+            name_ptrs.len() as i32,
+            only_if_exists as i32,
+            atoms.as_mut_ptr(),
+        );
+    }
+    Ok(atoms)
+}
+
+/// Looks up the name the server has registered for `atom`, or `None` if
+/// `atom` isn't a valid atom on this connection.
+pub fn x_get_atom_name(display: &mut DoNotFree<cdef::Display>, atom: cdef::Atom) -> Option<String> {
+    let raw = unsafe { cdef::XGetAtomName(display.data, atom) };
+    if raw.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(raw) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { cdef::XFree(raw as *mut c_void) };
+    Some(name)
+}
+
+/// Caches atom ids for names already interned on a display, so repeated
+/// lookups of common atoms (`WM_PROTOCOLS`, `WM_DELETE_WINDOW`,
+/// `_NET_WM_STATE`) avoid a round-trip to the server. A cache is specific to
+/// the display it was used with; don't share one across connections.
+#[derive(Default)]
+pub struct AtomCache {
+    atoms: HashMap<String, cdef::Atom>,
+}
+
+impl AtomCache {
+    pub fn new() -> AtomCache {
+        AtomCache::default()
+    }
+
+    /// Like `x_intern_atom`, but serves the atom out of the cache when
+    /// `name` has already been interned through this cache before.
+    pub fn intern(
+        &mut self,
+        display: &mut DoNotFree<cdef::Display>,
+        name: &str,
+        only_if_exists: bool,
+    ) -> Result<Option<cdef::Atom>, XlibError> {
+        if let Some(atom) = self.atoms.get(name) {
+            return Ok(Some(*atom));
+        }
+        let atom = x_intern_atom(display, name, only_if_exists)?;
+        if let Some(atom) = atom {
+            self.atoms.insert(name.to_string(), atom);
+        }
+        Ok(atom)
+    }
+}